@@ -1,10 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::io::{Read, Write};
 use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
 use std::sync::{mpsc, Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use signal_hook::consts::signal::{SIGINT, SIGTERM};
 use signal_hook::flag as signal_flag;
@@ -13,8 +14,11 @@ use signal_hook::flag as signal_flag;
 // 一个简单的线程池
 // ===================================
 struct ThreadPool {
-    tx: mpsc::Sender<Message>, // 用于发送任务到线程池
+    tx: mpsc::SyncSender<Message>, // 用于发送任务到线程池（有界队列）
     workers: Vec<thread::JoinHandle<()>>, // 存储工作线程的句柄
+    capacity: usize, // 队列容量，用于统计展示
+    depth: Arc<AtomicUsize>, // 当前排队（尚未被 worker 取走）的任务数
+    rejected: Arc<AtomicUsize>, // 因队列已满而被拒绝的任务数
 }
 
 // 消息类型，代表任务和终止信号
@@ -24,26 +28,38 @@ enum Message {
 }
 
 impl ThreadPool {
-    // 创建新的线程池，`size` 为线程数
-    fn new(size: usize) -> Self {
+    // 创建新的线程池，`size` 为线程数，`capacity` 为有界队列深度
+    fn new(size: usize, capacity: usize) -> Self {
         assert!(size > 0); // 确保线程池大小大于 0
+        assert!(capacity > 0); // 确保队列容量大于 0
 
-        let (tx, rx) = mpsc::channel::<Message>(); // 创建消息通道
+        let (tx, rx) = mpsc::sync_channel::<Message>(capacity); // 创建有界消息通道
         let rx = Arc::new(Mutex::new(rx)); // 使用 Arc 和 Mutex 保证多线程安全
 
+        let depth = Arc::new(AtomicUsize::new(0)); // 当前排队任务数
+        let rejected = Arc::new(AtomicUsize::new(0)); // 被拒绝任务数
+
         let mut workers = Vec::with_capacity(size); // 分配空间给工作线程
 
         // 创建并启动指定数量的工作线程
         for id in 0..size {
             let rx = Arc::clone(&rx); // 克隆 Arc，确保线程间共享
+            let depth = Arc::clone(&depth); // 共享队列深度计数
 
             // 创建一个新的工作线程
             let handle = thread::Builder::new()
                 .name(format!("worker-{id}"))
                 .spawn(move || loop {
                     match rx.lock().unwrap().recv() { // 获取任务
-                        Ok(Message::Job(job)) => job(), // 执行任务
-                        Ok(Message::Terminate) | Err(_) => break, // 终止信号，退出循环
+                        Ok(Message::Job(job)) => {
+                            depth.fetch_sub(1, Ordering::SeqCst); // 出队，深度减一
+                            job(); // 执行任务
+                        }
+                        Ok(Message::Terminate) | Err(_) => {
+                            // 终止信号：正在执行的任务已经跑完，打印后退出
+                            eprintln!("Shutting down worker {id}");
+                            break;
+                        }
                     }
                 })
                 .expect("spawn worker"); // 创建线程失败时 panic
@@ -51,28 +67,51 @@ impl ThreadPool {
             workers.push(handle); // 将线程句柄保存到 workers 中
         }
 
-        ThreadPool { tx, workers } // 返回线程池实例
+        ThreadPool { tx, workers, capacity, depth, rejected } // 返回线程池实例
     }
 
-    // 向线程池发送终止信号
-    fn shutdown(&mut self) {
-        // 向每个 worker 发送一个终止信号
-        for _ in 0..self.workers.len() {
-            let _ = self.tx.send(Message::Terminate);
-        }
+    // 优雅关机：排空在途任务，最多等待 `timeout`
+    //
+    // 在后台线程里先给每个 worker 发一个终止信号（排在已入队任务之后，
+    // 因此队列里的连接会被处理完），再 join 所有 worker。主线程用
+    // `recv_timeout` 给整个 drain 过程设上限，超时后放弃剩余 worker。
+    fn shutdown(&mut self, timeout: Duration) {
+        // 把发送终止信号和 join 都交给后台线程，主线程只负责计时
+        let workers = std::mem::take(&mut self.workers);
+        let tx = self.tx.clone();
+        let (done_tx, done_rx) = mpsc::channel();
+        thread::spawn(move || {
+            // 向每个 worker 发送一个终止信号（队列满时会阻塞，因此也在计时范围内）
+            for _ in 0..workers.len() {
+                let _ = tx.send(Message::Terminate);
+            }
+            for h in workers {
+                let _ = h.join();
+            }
+            let _ = done_tx.send(());
+        });
 
-        // 等待每个工作线程结束
-        while let Some(h) = self.workers.pop() {
-            let _ = h.join();
+        // 在超时时间内等待排空完成，否则放弃剩余 worker
+        if done_rx.recv_timeout(timeout).is_err() {
+            eprintln!("drain timed out after {timeout:?}; abandoning remaining workers");
         }
     }
 
-    // 向线程池发送任务
-    fn execute<F>(&self, job: F)
+    // 向线程池提交任务；队列已满时返回 `Err` 表示拒绝
+    fn execute<F>(&self, job: F) -> Result<(), ()>
     where
         F: FnOnce() + Send + 'static, // 任务类型，必须实现 FnOnce，并且能够发送到其他线程
     {
-        let _ = self.tx.send(Message::Job(Box::new(job))); // 发送任务
+        self.depth.fetch_add(1, Ordering::SeqCst); // 乐观地先占一个队列位置
+        match self.tx.try_send(Message::Job(Box::new(job))) {
+            Ok(()) => Ok(()),
+            Err(_) => {
+                // 队列已满：回滚深度计数并记一次拒绝
+                self.depth.fetch_sub(1, Ordering::SeqCst);
+                self.rejected.fetch_add(1, Ordering::SeqCst);
+                Err(())
+            }
+        }
     }
 }
 
@@ -91,19 +130,218 @@ impl Drop for ThreadPool {
     }
 }
 
+// ===================================
+// 有界响应缓存（LRU + TTL）
+// ===================================
+// 单条缓存项：渲染好的响应体、插入时间、命中计数
+struct CacheEntry {
+    body: Vec<u8>,   // 缓存的响应体
+    inserted: Instant, // 插入时刻，用于 TTL 过期判断
+    hits: usize,     // 该路径被命中的次数
+}
+
+// 有界响应缓存：最多 `max_entries` 条，超出按 LRU 淘汰，过期按 TTL 清除
+struct ResponseCache {
+    map: HashMap<String, CacheEntry>,
+    order: VecDeque<String>, // 访问顺序，队首是最久未用，队尾是最近使用
+    max_entries: usize,
+    ttl: Duration,
+    hits: usize,      // 累计命中次数
+    misses: usize,    // 累计未命中次数
+    evictions: usize, // 累计 LRU 淘汰次数
+}
+
+impl ResponseCache {
+    // 创建缓存：`max_entries` 为容量上限，`ttl` 为条目存活时间
+    fn new(max_entries: usize, ttl: Duration) -> Self {
+        assert!(max_entries > 0);
+        ResponseCache {
+            map: HashMap::new(),
+            order: VecDeque::new(),
+            max_entries,
+            ttl,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    // 取出一条未过期的缓存，同时更新 LRU 顺序与命中统计
+    //
+    // 命中返回响应体副本；未命中或已过期返回 `None`，过期条目会被顺手清除。
+    fn get(&mut self, key: &str) -> Option<Vec<u8>> {
+        // 先判断是否存在且未过期，过期则清除
+        if let Some(entry) = self.map.get(key) {
+            if entry.inserted.elapsed() > self.ttl {
+                self.map.remove(key);
+                self.touch_remove(key);
+                self.misses += 1;
+                return None;
+            }
+        } else {
+            self.misses += 1;
+            return None;
+        }
+
+        // 命中：提升到队尾（最近使用），累加计数
+        self.touch_remove(key);
+        self.order.push_back(key.to_string());
+        let entry = self.map.get_mut(key).unwrap();
+        entry.hits += 1;
+        self.hits += 1;
+        Some(entry.body.clone())
+    }
+
+    // 插入一条缓存；超过容量时按 LRU 淘汰队首
+    fn insert(&mut self, key: String, body: Vec<u8>) {
+        if !self.map.contains_key(&key) {
+            while self.map.len() >= self.max_entries {
+                match self.order.pop_front() {
+                    Some(lru) => {
+                        if self.map.remove(&lru).is_some() {
+                            self.evictions += 1;
+                        }
+                    }
+                    None => break,
+                }
+            }
+        } else {
+            // 覆盖已有条目时，先把旧的访问顺序摘掉
+            self.touch_remove(&key);
+        }
+
+        self.order.push_back(key.clone());
+        self.map.insert(
+            key,
+            CacheEntry {
+                body,
+                inserted: Instant::now(),
+                hits: 0,
+            },
+        );
+    }
+
+    // 从访问顺序队列里移除某个 key（命中/淘汰时维护用）
+    fn touch_remove(&mut self, key: &str) {
+        if let Some(pos) = self.order.iter().position(|k| k == key) {
+            self.order.remove(pos);
+        }
+    }
+
+    // 命中率：命中数 / 总查询数
+    fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+// ===================================
+// 静态文件路由表
+// ===================================
+// 一条路由规则：把 URL 前缀映射到文件系统的某个根目录
+struct Route {
+    prefix: String, // URL 前缀，例如 "/"
+    root: PathBuf,   // 该前缀对应的文件系统根目录
+}
+
+// 路由表：按顺序匹配第一条命中的前缀
+struct Router {
+    routes: Vec<Route>,
+}
+
+// 解析后的静态文件：既给 serve_request 用作缓存 key，也用来读取文件
+struct Resolved {
+    key: String,  // 去掉查询串后的 URL 路径（含前缀），作为缓存 key
+    path: PathBuf, // 实际落到磁盘上的文件路径
+}
+
+impl Router {
+    // 用默认规则创建路由表：把 "/" 映射到给定的静态文件目录
+    fn new(root: impl Into<PathBuf>) -> Self {
+        Router {
+            routes: vec![Route {
+                prefix: "/".to_string(),
+                root: root.into(),
+            }],
+        }
+    }
+
+    // 把 URL 路径解析成磁盘路径，并做目录穿越保护
+    //
+    // 返回 `None` 表示没有任何前缀命中或路径被拒绝；调用方据此返回 404。
+    // 任何包含 `..` 段的路径都会被拒绝，避免逃出配置的根目录。
+    fn resolve(&self, url_path: &str) -> Option<Resolved> {
+        // 去掉查询串，只保留路径部分
+        let url_path = url_path.split('?').next().unwrap_or(url_path);
+
+        for route in &self.routes {
+            let Some(rest) = url_path.strip_prefix(&route.prefix) else {
+                continue;
+            };
+
+            // 拒绝任何带 `..` 或根组件的路径，防止目录穿越
+            let rel = Path::new(rest);
+            if rel.components().any(|c| !matches!(c, Component::Normal(_))) {
+                return None;
+            }
+
+            // 空路径（如 "/"）落到 index.html
+            let mut path = route.root.clone();
+            if rest.is_empty() {
+                path.push("index.html");
+            } else {
+                path.push(rel);
+            }
+
+            return Some(Resolved {
+                key: format!("{}{}", route.prefix, rest),
+                path,
+            });
+        }
+        None
+    }
+}
+
+// 根据文件扩展名猜测 Content-Type
+fn content_type_for(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
 // ===================================
 // HTTP 服务器（阻塞 I/O）
 // ===================================
 fn main() -> std::io::Result<()> {
+    // 解析命令行参数
+    let cfg = parse_args();
+
     // 绑定 TCP 监听器，监听 7878 端口
     let listener = TcpListener::bind(("0.0.0.0", 7878))?;
     listener.set_nonblocking(true)?; // 设置非阻塞，便于后续轮询
-    println!("listening on http://0.0.0.0:7878 (GET /path → echo /path, task = sleep 1s)");
+    println!("listening on http://0.0.0.0:7878 (serving ./www, task = sleep 1s)");
 
-    let mut pool = ThreadPool::new(num_cpus()); // 创建线程池，线程数为 CPU 核心数
+    let workers = num_cpus();
+    let mut pool = ThreadPool::new(workers, workers * 8); // 线程池 + 8 倍于线程数的队列容量
 
-    // 线程安全的缓存：记录访问路径和次数
-    let cache = Arc::new(Mutex::new(HashMap::<String, usize>::new()));
+    // 静态文件路由表：把 "/" 映射到 ./www 目录
+    let router = Arc::new(Router::new("www"));
+
+    // 线程安全的有界响应缓存（LRU + TTL）
+    let cache = Arc::new(Mutex::new(ResponseCache::new(cfg.cache_max, cfg.cache_ttl)));
 
     // ---- 优雅关机：信号 + 原子标记 ----
     let shutdown = Arc::new(AtomicBool::new(false));
@@ -113,6 +351,9 @@ fn main() -> std::io::Result<()> {
     signal_flag::register(SIGTERM, Arc::clone(&shutdown))
         .expect("register SIGTERM");
 
+    // 已接受的连接计数，用于 --max-requests 模式
+    let mut accepted = 0usize;
+
     // 主循环：轮询 TCP 连接并检查关机标记
     loop {
         if shutdown.load(Ordering::Relaxed) { // 检查是否需要关机
@@ -121,13 +362,39 @@ fn main() -> std::io::Result<()> {
         }
 
         match listener.accept() { // 接受新的 TCP 连接
-            Ok((stream, _addr)) => {
+            Ok((mut stream, _addr)) => {
                 let cache = Arc::clone(&cache); // 克隆缓存的 Arc
-                pool.execute(move || { // 执行任务：处理连接
-                    if let Err(e) = handle_conn(stream, cache) {
-                        eprintln!("conn error: {e}");
+                let router = Arc::clone(&router); // 克隆路由表的 Arc
+                // 把一个句柄交给 worker，主线程保留原句柄用于过载时回 503。
+                // 正常路径上原句柄随即离开作用域关闭，worker 只持有一个 fd；
+                // try_clone 失败（常见于 fd 耗尽，正是过载信号）也不致命：
+                // 用已有句柄直接回 503，而不是用 `?` 把整个 server 拖垮。
+                match stream.try_clone() {
+                    Ok(worker_stream) => {
+                        let submit = pool.execute(move || { // 执行任务：处理连接
+                            if let Err(e) = handle_conn(worker_stream, router, cache) {
+                                eprintln!("conn error: {e}");
+                            }
+                        });
+                        if submit.is_err() {
+                            // 队列已满：不再接活，立刻告知客户端稍后重试
+                            let _ = send_service_unavailable(&mut stream);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("overloaded: cannot clone stream ({e}); replying 503");
+                        let _ = send_service_unavailable(&mut stream);
+                    }
+                }
+
+                // --max-requests 模式：处理完 N 个连接后走同一套优雅关机流程
+                accepted += 1;
+                if let Some(max) = cfg.max_requests {
+                    if accepted >= max {
+                        eprintln!("\nreached --max-requests {max}; shutting down…");
+                        break;
                     }
-                });
+                }
             }
             Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
                 // 如果没有新的连接，稍微休息，避免忙等
@@ -140,21 +407,44 @@ fn main() -> std::io::Result<()> {
         }
     }
 
-    pool.shutdown(); // 关停线程池，等待工作线程退出
+    pool.shutdown(cfg.drain_timeout); // 关停线程池，排空在途任务（带超时上限）
 
-    // 最后打印缓存统计信息
-    print_cache_stats(&cache);
+    // 最后打印缓存与队列统计信息
+    print_cache_stats(&cache, &pool);
 
     Ok(())
 }
 
-// 打印缓存访问统计信息
-fn print_cache_stats(cache: &Arc<Mutex<HashMap<String, usize>>>) {
-    let mut items: Vec<(String, usize)> = {
-        let map = cache.lock().unwrap(); // 锁定缓存
-        map.iter().map(|(k, &v)| (k.clone(), v)).collect() // 克隆数据避免锁定期间修改
+// 过载时立即返回 503，并附带 Retry-After 头
+fn send_service_unavailable(stream: &mut TcpStream) -> std::io::Result<()> {
+    let resp = b"HTTP/1.1 503 Service Unavailable\r\nRetry-After: 1\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+    stream.write_all(resp)?;
+    stream.flush()
+}
+
+// 打印缓存访问统计信息，以及线程池队列的深度与拒绝数
+fn print_cache_stats(cache: &Arc<Mutex<ResponseCache>>, pool: &ThreadPool) {
+    let (mut items, hits, misses, evictions, hit_rate) = {
+        let c = cache.lock().unwrap(); // 锁定缓存
+        let items: Vec<(String, usize)> =
+            c.map.iter().map(|(k, e)| (k.clone(), e.hits)).collect(); // 克隆数据避免锁定期间修改
+        (items, c.hits, c.misses, c.evictions, c.hit_rate())
     };
-    items.sort_by(|a, b| b.1.cmp(&a.1)); // 按访问次数降序排序
+    items.sort_by_key(|e| std::cmp::Reverse(e.1)); // 按命中次数降序排序
+
+    println!("\n==== Queue Stats ====");
+    println!(
+        "capacity={}  depth={}  rejected={}",
+        pool.capacity,
+        pool.depth.load(Ordering::SeqCst),
+        pool.rejected.load(Ordering::SeqCst)
+    );
+
+    println!("\n==== Cache Stats ====");
+    println!(
+        "hits={hits}  misses={misses}  evictions={evictions}  hit_rate={:.1}%",
+        hit_rate * 100.0
+    );
 
     println!("\n==== Access Stats ====");
     if items.is_empty() {
@@ -166,98 +456,302 @@ fn print_cache_stats(cache: &Arc<Mutex<HashMap<String, usize>>>) {
     }
 }
 
-// 处理 TCP 连接：读取请求并返回响应
-fn handle_conn(mut stream: TcpStream, cache: Arc<Mutex<HashMap<String, usize>>>) -> std::io::Result<()> {
-    let mut buf = [0u8; 8192]; // 缓存请求数据
-    let mut n = 0usize;
+// 空闲 keep-alive 连接的读超时：超过这个时间没有新请求就回收连接
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(15);
+
+// 头部块大小上限（字节），超过则返回 400，避免无界缓冲耗尽内存
+const MAX_HEADER_BYTES: usize = 16 * 1024;
+
+// 请求体大小上限（字节），超过则返回 413，避免超大 Content-Length 耗尽内存
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+// 解析好的请求头：请求行中的方法与路径 + 需要用到的头字段（版本号已并入 keep_alive）
+struct RequestHead {
+    method: String,
+    path: String,
+    content_length: usize,
+    keep_alive: bool,
+}
+
+// 处理 TCP 连接：在一条连接上循环服务多个请求（HTTP/1.1 keep-alive）
+fn handle_conn(
+    mut stream: TcpStream,
+    router: Arc<Router>,
+    cache: Arc<Mutex<ResponseCache>>,
+) -> std::io::Result<()> {
+    // 给空闲的 keep-alive 连接设一个读超时，避免长期占用 worker
+    stream.set_read_timeout(Some(KEEP_ALIVE_TIMEOUT))?;
+
+    // 可增长的读缓冲，可以跨多次 read 累积，也保留 pipelined 的富余字节
+    let mut buf: Vec<u8> = Vec::new();
 
-    // 循环读取直到找到 CRLF 或缓冲区满
     loop {
-        if n == buf.len() {
-            break;
-        }
-        let readn = stream.read(&mut buf[n..])?; // 读取数据到缓存
-        if readn == 0 {
-            return Ok(()); // 客户端关闭连接
-        }
-        n += readn;
-
-        if let Some(line_end) = find_crlf(&buf[..n]) { // 找到请求行的结束
-            let line = &buf[..line_end]; // 获取请求行
-            let path = parse_path_from_request_line(line).unwrap_or_else(|| "/".to_string());
-
-            // ========= cache 逻辑 =========
-            // 如果缓存命中，直接返回路径和 emoji
-            let mut hit = false;
-            {
-                let mut map = cache.lock().unwrap();
-                if let Some(cnt) = map.get_mut(&path) {
-                    *cnt += 1;
-                    hit = true;
-                }
+        // 先读满整个头部块（直到 \r\n\r\n）
+        let header_end = loop {
+            if let Some(pos) = find_headers_end(&buf) {
+                break pos;
             }
-
-            if hit {
-                let body = format!("{path} 🙂");
-                let header = format!(
-                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
-                    body.as_bytes().len()
-                );
-                stream.write_all(header.as_bytes())?;
-                stream.write_all(body.as_bytes())?;
-                stream.flush()?;
-                return Ok(());
+            // 头部块过大：拒绝，避免无界缓冲被撑爆内存
+            if buf.len() > MAX_HEADER_BYTES {
+                return send_simple(&mut stream, 400, "Bad Request", false);
+            }
+            match read_more(&mut stream, &mut buf)? {
+                // 连接关闭或空闲超时：正常收尾
+                ReadOutcome::Eof | ReadOutcome::Idle => return Ok(()),
+                ReadOutcome::Data => {}
             }
+        };
+
+        // 解析请求行与头部；解析失败直接 400 并关闭
+        let Some(head) = parse_head(&buf[..header_end]) else {
+            return send_simple(&mut stream, 400, "Bad Request", false);
+        };
 
-            // 未命中缓存，模拟任务处理（延迟 1 秒）
-            thread::sleep(Duration::from_secs(1));
+        // 请求体过大：拒绝，避免客户端用巨大的 Content-Length 撑爆内存
+        if head.content_length > MAX_BODY_BYTES {
+            return send_simple(&mut stream, 413, "Payload Too Large", false);
+        }
 
-            // 插入或更新缓存
-            {
-                let mut map = cache.lock().unwrap();
-                let entry = map.entry(path.clone()).or_insert(0);
-                *entry += 1;
+        // 消费请求体，保证后续请求从干净的边界开始
+        let body_start = header_end + 4; // 跳过结尾的 \r\n\r\n
+        let consumed = body_start + head.content_length;
+        while buf.len() < consumed {
+            match read_more(&mut stream, &mut buf)? {
+                ReadOutcome::Eof | ReadOutcome::Idle => return Ok(()),
+                ReadOutcome::Data => {}
             }
+        }
+
+        // 服务这一个请求，拿到本次是否应当保持连接
+        let keep_alive = serve_request(&mut stream, &router, &cache, &head)?;
 
-            // 返回路径作为响应
-            let body = path.as_bytes();
-            let header = format!(
-                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
-                body.len()
-            );
-            stream.write_all(header.as_bytes())?;
-            stream.write_all(body)?;
-            stream.flush()?;
+        if !keep_alive {
             return Ok(());
         }
+
+        // 丢弃已处理的字节，余下的留给下一个（可能已 pipelined 的）请求
+        buf.drain(..consumed);
     }
+}
 
-    // 如果没有解析到有效请求行，返回 400 错误
-    let resp = b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
-    stream.write_all(resp)?;
-    stream.flush()?;
-    Ok(())
+// 服务单个已解析的请求，返回是否保持连接
+fn serve_request(
+    stream: &mut TcpStream,
+    router: &Router,
+    cache: &Arc<Mutex<ResponseCache>>,
+    head: &RequestHead,
+) -> std::io::Result<bool> {
+    let keep_alive = head.keep_alive;
+
+    // 只服务 GET，其他方法返回 405
+    if head.method != "GET" {
+        send_simple(stream, 405, "Method Not Allowed", keep_alive)?;
+        return Ok(keep_alive);
+    }
+
+    // 解析到磁盘路径；命中目录穿越或无匹配前缀则返回 404
+    let Some(resolved) = router.resolve(&head.path) else {
+        send_not_found(stream, router, keep_alive)?;
+        return Ok(keep_alive);
+    };
+
+    // ========= cache 逻辑 =========
+    // 缓存命中：用缓存好的响应体直接（快速）返回
+    if let Some(body) = cache.lock().unwrap().get(&resolved.key) {
+        send_file(stream, &resolved.path, &body, keep_alive)?;
+        return Ok(keep_alive);
+    }
+
+    // 未命中缓存，模拟任务处理（延迟 1 秒）
+    thread::sleep(Duration::from_secs(1));
+
+    // 读取文件并返回；成功则写入缓存，读不到则 404（不缓存）
+    match std::fs::read(&resolved.path) {
+        Ok(body) => {
+            cache.lock().unwrap().insert(resolved.key.clone(), body.clone());
+            send_file(stream, &resolved.path, &body, keep_alive)?;
+        }
+        Err(_) => send_not_found(stream, router, keep_alive)?,
+    }
+    Ok(keep_alive)
+}
+
+// 一次 read 的结果：读到了数据 / 对端关闭 / 空闲超时
+enum ReadOutcome {
+    Data,
+    Eof,
+    Idle,
 }
 
-// 查找 CRLF（\r\n）的位置
-fn find_crlf(buf: &[u8]) -> Option<usize> {
-    for i in 0..buf.len().saturating_sub(1) {
-        if buf[i] == b'\r' && buf[i + 1] == b'\n' {
-            return Some(i); // 返回 CRLF 结束的位置
+// 向缓冲追加一次 read 的数据；区分 EOF 与空闲超时
+fn read_more(stream: &mut TcpStream, buf: &mut Vec<u8>) -> std::io::Result<ReadOutcome> {
+    let mut chunk = [0u8; 8192];
+    match stream.read(&mut chunk) {
+        Ok(0) => Ok(ReadOutcome::Eof),
+        Ok(n) => {
+            buf.extend_from_slice(&chunk[..n]);
+            Ok(ReadOutcome::Data)
         }
+        // set_read_timeout 触发时，不同平台给出 WouldBlock 或 TimedOut
+        Err(ref e)
+            if e.kind() == std::io::ErrorKind::WouldBlock
+                || e.kind() == std::io::ErrorKind::TimedOut =>
+        {
+            Ok(ReadOutcome::Idle)
+        }
+        Err(e) => Err(e),
     }
-    None
 }
 
-// 解析请求行中的路径部分
-fn parse_path_from_request_line(line: &[u8]) -> Option<String> {
-    let mut parts = line.split(|&b| b == b' ');
-    let method = parts.next()?;
-    if method != b"GET" {
-        return Some("/".to_string()); // 只处理 GET 请求，其他的返回 "/"
+// 连接头的取值：keep-alive 时为 "keep-alive"，否则 "close"
+fn connection_value(keep_alive: bool) -> &'static str {
+    if keep_alive {
+        "keep-alive"
+    } else {
+        "close"
+    }
+}
+
+// 返回一个静态文件，Content-Type 由扩展名决定
+fn send_file(
+    stream: &mut TcpStream,
+    path: &Path,
+    body: &[u8],
+    keep_alive: bool,
+) -> std::io::Result<()> {
+    let header = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n",
+        content_type_for(path),
+        body.len(),
+        connection_value(keep_alive)
+    );
+    stream.write_all(header.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+// 返回 404：优先使用路由根目录下的 404.html，否则退回纯文本
+fn send_not_found(stream: &mut TcpStream, router: &Router, keep_alive: bool) -> std::io::Result<()> {
+    let body = router
+        .routes
+        .first()
+        .and_then(|r| std::fs::read(r.root.join("404.html")).ok())
+        .unwrap_or_else(|| b"404 Not Found".to_vec());
+    let header = format!(
+        "HTTP/1.1 404 Not Found\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n",
+        body.len(),
+        connection_value(keep_alive)
+    );
+    stream.write_all(header.as_bytes())?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
+
+// 返回一个没有响应体的状态行，用于 400 / 405 等
+fn send_simple(
+    stream: &mut TcpStream,
+    status: u16,
+    reason: &str,
+    keep_alive: bool,
+) -> std::io::Result<()> {
+    let resp = format!(
+        "HTTP/1.1 {status} {reason}\r\nConnection: {}\r\nContent-Length: 0\r\n\r\n",
+        connection_value(keep_alive)
+    );
+    stream.write_all(resp.as_bytes())?;
+    stream.flush()
+}
+
+// 查找头部块结束位置（\r\n\r\n），返回其起始下标
+fn find_headers_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n")
+}
+
+// 解析请求行与相关头字段
+fn parse_head(head: &[u8]) -> Option<RequestHead> {
+    let text = String::from_utf8_lossy(head);
+    let mut lines = text.split("\r\n");
+
+    // 请求行：METHOD PATH VERSION
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    let version = request_line.next().unwrap_or("HTTP/1.0").to_string();
+
+    // 逐行解析头部，取出 Content-Length 与 Connection
+    let mut content_length = 0usize;
+    let mut connection: Option<String> = None;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let name = name.trim().to_ascii_lowercase();
+        let value = value.trim();
+        match name.as_str() {
+            "content-length" => content_length = value.parse().unwrap_or(0),
+            "connection" => connection = Some(value.to_ascii_lowercase()),
+            _ => {}
+        }
     }
-    let path = parts.next().unwrap_or(b"/");
-    Some(String::from_utf8_lossy(path).into_owned())
+
+    // HTTP/1.1 默认 keep-alive，除非显式 close；HTTP/1.0 则相反
+    let keep_alive = match connection.as_deref() {
+        Some("close") => false,
+        Some("keep-alive") => true,
+        _ => version == "HTTP/1.1",
+    };
+
+    Some(RequestHead {
+        method,
+        path,
+        content_length,
+        keep_alive,
+    })
+}
+
+// 运行期配置，来自命令行参数
+struct Config {
+    max_requests: Option<usize>, // --max-requests：处理 N 个连接后关机
+    drain_timeout: Duration,     // --drain-timeout：关机排空上限
+    cache_max: usize,            // --cache-max：缓存最大条目数
+    cache_ttl: Duration,         // --cache-ttl：缓存条目存活时间
+}
+
+// 解析命令行参数
+fn parse_args() -> Config {
+    let mut cfg = Config {
+        max_requests: None,
+        drain_timeout: Duration::from_secs(10),
+        cache_max: 128,
+        cache_ttl: Duration::from_secs(30),
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-requests" => {
+                cfg.max_requests = args.next().and_then(|v| v.parse().ok());
+            }
+            "--drain-timeout" => {
+                if let Some(secs) = args.next().and_then(|v| v.parse().ok()) {
+                    cfg.drain_timeout = Duration::from_secs(secs);
+                }
+            }
+            "--cache-max" => {
+                if let Some(n) = args.next().and_then(|v| v.parse().ok()) {
+                    cfg.cache_max = n;
+                }
+            }
+            "--cache-ttl" => {
+                if let Some(secs) = args.next().and_then(|v| v.parse().ok()) {
+                    cfg.cache_ttl = Duration::from_secs(secs);
+                }
+            }
+            other => eprintln!("ignoring unknown argument: {other}"),
+        }
+    }
+
+    cfg
 }
 
 // 获取 CPU 核心数
@@ -268,3 +762,106 @@ fn num_cpus() -> usize {
         .max(2) // 至少保证有两个线程
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ---- Router::resolve ----
+
+    #[test]
+    fn resolve_maps_root_to_index_html() {
+        let router = Router::new("www");
+        let r = router.resolve("/").expect("root should resolve");
+        assert_eq!(r.key, "/");
+        assert_eq!(r.path, PathBuf::from("www").join("index.html"));
+    }
+
+    #[test]
+    fn resolve_maps_named_file() {
+        let router = Router::new("www");
+        let r = router.resolve("/style.css").expect("file should resolve");
+        assert_eq!(r.key, "/style.css");
+        assert_eq!(r.path, PathBuf::from("www").join("style.css"));
+    }
+
+    #[test]
+    fn resolve_strips_query_string() {
+        let router = Router::new("www");
+        let r = router.resolve("/a.html?v=1").expect("should resolve");
+        assert_eq!(r.path, PathBuf::from("www").join("a.html"));
+    }
+
+    #[test]
+    fn resolve_rejects_parent_dir_traversal() {
+        let router = Router::new("www");
+        assert!(router.resolve("/../etc/passwd").is_none());
+        assert!(router.resolve("/sub/../../secret").is_none());
+    }
+
+    // ---- parse_head ----
+
+    fn head(bytes: &str) -> RequestHead {
+        parse_head(bytes.as_bytes()).expect("should parse")
+    }
+
+    #[test]
+    fn parse_head_reads_method_path_and_content_length() {
+        let h = head("POST /submit HTTP/1.1\r\nContent-Length: 42\r\nHost: x");
+        assert_eq!(h.method, "POST");
+        assert_eq!(h.path, "/submit");
+        assert_eq!(h.content_length, 42);
+    }
+
+    #[test]
+    fn parse_head_http11_defaults_to_keep_alive() {
+        assert!(head("GET / HTTP/1.1\r\nHost: x").keep_alive);
+    }
+
+    #[test]
+    fn parse_head_http10_defaults_to_close() {
+        assert!(!head("GET / HTTP/1.0\r\nHost: x").keep_alive);
+    }
+
+    #[test]
+    fn parse_head_honors_explicit_connection_header() {
+        assert!(!head("GET / HTTP/1.1\r\nConnection: close").keep_alive);
+        assert!(head("GET / HTTP/1.0\r\nConnection: keep-alive").keep_alive);
+    }
+
+    // ---- ResponseCache ----
+
+    #[test]
+    fn cache_counts_hits_and_misses() {
+        let mut cache = ResponseCache::new(4, Duration::from_secs(60));
+        assert!(cache.get("/a").is_none()); // miss
+        cache.insert("/a".to_string(), b"body".to_vec());
+        assert_eq!(cache.get("/a").as_deref(), Some(&b"body"[..])); // hit
+        assert_eq!(cache.hits, 1);
+        assert_eq!(cache.misses, 1);
+        assert!((cache.hit_rate() - 0.5).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let mut cache = ResponseCache::new(2, Duration::from_secs(60));
+        cache.insert("/a".to_string(), b"a".to_vec());
+        cache.insert("/b".to_string(), b"b".to_vec());
+        // 访问 /a，使 /b 成为最久未用
+        assert!(cache.get("/a").is_some());
+        cache.insert("/c".to_string(), b"c".to_vec());
+        assert!(cache.map.contains_key("/a"));
+        assert!(cache.map.contains_key("/c"));
+        assert!(!cache.map.contains_key("/b")); // /b 被淘汰
+        assert_eq!(cache.evictions, 1);
+    }
+
+    #[test]
+    fn cache_expires_entries_past_ttl() {
+        let mut cache = ResponseCache::new(4, Duration::from_millis(1));
+        cache.insert("/a".to_string(), b"a".to_vec());
+        thread::sleep(Duration::from_millis(5));
+        assert!(cache.get("/a").is_none()); // 已过期
+        assert!(!cache.map.contains_key("/a")); // 过期项被顺手清除
+    }
+}
+